@@ -2,7 +2,9 @@ use ::std::sync::mpsc::{Sender, Receiver};
 use ::std::path::{Path, PathBuf};
 use ::std::fs::{self, Metadata};
 use ::std::ffi::OsString;
+use ::std::collections::HashSet;
 use ::tui::backend::Backend;
+use ::trash;
 
 use crate::Event;
 use crate::state::files::{Folder, FileOrFolder};
@@ -36,6 +38,10 @@ pub enum UiMode {
     Normal,
     ScreenTooSmall,
     DeleteFile(FileToDelete),
+    DeleteFlagged,
+    RestoreList,
+    Search(String),
+    FileInfo(FileMetadata),
     ErrorMessage(String),
 }
 
@@ -49,16 +55,20 @@ where B: Backend
     display: Display<B>,
     event_sender: Sender<Event>,
     ui_effects: UiEffects,
+    use_trash: bool,
+    trashed_files: Vec<FileToDelete>,
+    restore_list_selected_index: usize,
+    flagged_files: HashSet<Vec<OsString>>,
 }
 
 impl <B>App <B>
 where B: Backend
 {
-    pub fn new (terminal_backend: B, path_in_filesystem: PathBuf, event_sender: Sender<Event>) -> Self {
+    pub fn new (terminal_backend: B, path_in_filesystem: PathBuf, event_sender: Sender<Event>, use_trash: bool, show_hidden_files: bool) -> Self {
         let display = Display::new(terminal_backend);
         let board = Board::new(&Folder::new(&path_in_filesystem));
         let base_folder = Folder::new(&path_in_filesystem); // TODO: better
-        let file_tree = FileTree::new(base_folder, path_in_filesystem);
+        let file_tree = FileTree::new(base_folder, path_in_filesystem, show_hidden_files);
         let ui_effects = UiEffects::new();
         App {
             is_running: true,
@@ -68,6 +78,10 @@ where B: Backend
             ui_mode: UiMode::Loading,
             event_sender,
             ui_effects,
+            use_trash,
+            trashed_files: vec![],
+            restore_list_selected_index: 0,
+            flagged_files: HashSet::new(),
         }
     }
     pub fn start (&mut self, receiver: Receiver<Instruction>) {
@@ -76,9 +90,13 @@ where B: Backend
     }
     pub fn render_and_update_board (&mut self) {
         let current_folder = self.file_tree.get_current_folder();
-        self.board.change_files(&current_folder); // TODO: rename to change_tiles
+        self.board.change_files(&current_folder, self.file_tree.show_hidden_files); // TODO: rename to change_tiles
         self.render();
     }
+    pub fn toggle_hidden_files(&mut self) {
+        self.file_tree.show_hidden_files = !self.file_tree.show_hidden_files;
+        self.render_and_update_board();
+    }
     pub fn increment_loading_progress_indicator(&mut self) {
         self.ui_effects.increment_loading_progress_indicator();
     }
@@ -117,6 +135,10 @@ where B: Backend
     pub fn reset_ui_mode (&mut self) {
         match self.ui_mode {
             UiMode::Loading | UiMode::Normal => {},
+            UiMode::Search(_) => {
+                self.ui_mode = UiMode::Normal;
+                self.board.set_search_filter(None);
+            },
             _ => self.ui_mode = UiMode::Normal,
         };
     }
@@ -125,21 +147,57 @@ where B: Backend
         let _ = self.event_sender.send(Event::AppExit);
     }
     pub fn move_selected_right (&mut self) {
-        self.board.move_selected_right();
+        match &self.ui_mode {
+            UiMode::Search(query) => self.board.move_selected_right_matching(&query.clone()),
+            _ => self.board.move_selected_right(),
+        }
         self.render();
     }
     pub fn move_selected_left (&mut self) {
-        self.board.move_selected_left();
+        match &self.ui_mode {
+            UiMode::Search(query) => self.board.move_selected_left_matching(&query.clone()),
+            _ => self.board.move_selected_left(),
+        }
         self.render();
     }
     pub fn move_selected_down (&mut self) {
-        self.board.move_selected_down();
+        match &self.ui_mode {
+            UiMode::Search(query) => self.board.move_selected_down_matching(&query.clone()),
+            _ => self.board.move_selected_down(),
+        }
         self.render();
     }
     pub fn move_selected_up (&mut self) {
-        self.board.move_selected_up();
+        match &self.ui_mode {
+            UiMode::Search(query) => self.board.move_selected_up_matching(&query.clone()),
+            _ => self.board.move_selected_up(),
+        }
+        self.render();
+    }
+    pub fn start_search(&mut self) {
+        self.ui_mode = UiMode::Search(String::new());
+        self.board.set_search_filter(Some(String::new()));
         self.render();
     }
+    pub fn add_char_to_search(&mut self, character: char) {
+        if let UiMode::Search(query) = &mut self.ui_mode {
+            query.push(character);
+            self.board.set_search_filter(Some(query.clone()));
+        }
+        self.render();
+    }
+    pub fn remove_char_from_search(&mut self) {
+        if let UiMode::Search(query) = &mut self.ui_mode {
+            query.pop();
+            self.board.set_search_filter(Some(query.clone()));
+        }
+        self.render();
+    }
+    pub fn cancel_search(&mut self) {
+        self.ui_mode = UiMode::Normal;
+        self.board.set_search_filter(None);
+        self.render_and_update_board();
+    }
     pub fn enter_selected (&mut self) {
         if let Some(file_size_rect) = &self.board.currently_selected() {
             let selected_name = &file_size_rect.file_metadata.name;
@@ -148,6 +206,10 @@ where B: Backend
                     FileOrFolder::Folder(_) => {
                         self.file_tree.enter_folder(&selected_name);
                         self.board.reset_selected_index();
+                        if let UiMode::Search(_) = self.ui_mode {
+                            self.ui_mode = UiMode::Normal;
+                            self.board.set_search_filter(None);
+                        }
                         self.render_and_update_board();
                         let _ = self.event_sender.send(Event::PathChange);
                     }
@@ -183,38 +245,203 @@ where B: Backend
             self.render();
         }
     }
+    pub fn show_file_info(&mut self) {
+        if let Some(file_to_delete) = self.get_file_to_delete() {
+            self.ui_mode = UiMode::FileInfo(file_to_delete.file_metadata);
+            self.render();
+        }
+    }
+    pub fn current_full_path(&self) -> Option<PathBuf> {
+        Some(self.get_file_to_delete()?.full_path())
+    }
+    pub fn toggle_flag_on_selected(&mut self) {
+        if let Some(file_to_delete) = self.get_file_to_delete() {
+            if !self.flagged_files.remove(&file_to_delete.path_to_file) {
+                self.flagged_files.insert(file_to_delete.path_to_file);
+            }
+            self.sync_flagged_files_with_ui_effects();
+            self.render();
+        }
+    }
+    pub fn flag_all_in_current_folder(&mut self) {
+        for name in self.file_tree.names_in_current_folder() {
+            let mut path_to_file = self.file_tree.current_folder_names.clone();
+            path_to_file.push(name);
+            self.flagged_files.insert(path_to_file);
+        }
+        self.sync_flagged_files_with_ui_effects();
+        self.render();
+    }
+    pub fn reverse_flags(&mut self) {
+        for name in self.file_tree.names_in_current_folder() {
+            let mut path_to_file = self.file_tree.current_folder_names.clone();
+            path_to_file.push(name);
+            if !self.flagged_files.remove(&path_to_file) {
+                self.flagged_files.insert(path_to_file);
+            }
+        }
+        self.sync_flagged_files_with_ui_effects();
+        self.render();
+    }
+    pub fn clear_flags(&mut self) {
+        self.flagged_files.clear();
+        self.sync_flagged_files_with_ui_effects();
+        self.render();
+    }
+    fn sync_flagged_files_with_ui_effects(&mut self) {
+        self.ui_effects.flagged_files = self.flagged_files.clone();
+    }
+    fn flagged_files_to_delete(&self) -> Vec<FileToDelete> {
+        // shortest paths first, and drop any entry whose ancestor is also
+        // flagged, so a flagged parent folder is deleted before (and instead
+        // of) its flagged children
+        let mut sorted_paths: Vec<Vec<OsString>> = self.flagged_files.iter().cloned().collect();
+        sorted_paths.sort_by_key(|path_to_file| path_to_file.len());
+        let mut top_level_paths: Vec<Vec<OsString>> = vec![];
+        for path_to_file in sorted_paths {
+            let has_flagged_ancestor = top_level_paths.iter().any(|kept| path_to_file.starts_with(kept.as_slice()));
+            if !has_flagged_ancestor {
+                top_level_paths.push(path_to_file);
+            }
+        }
+        top_level_paths.into_iter().filter_map(|path_to_file| {
+            let file_metadata = self.file_tree.file_metadata_at_path(&path_to_file)?;
+            Some(FileToDelete {
+                path_in_filesystem: self.file_tree.path_in_filesystem.clone(),
+                path_to_file,
+                file_metadata,
+            })
+        }).collect()
+    }
+    pub fn prompt_flagged_deletion(&mut self) {
+        if !self.flagged_files.is_empty() {
+            self.ui_mode = UiMode::DeleteFlagged;
+            self.render();
+        }
+    }
+    pub fn flagged_files_count_and_size(&self) -> (usize, u64) {
+        let files_to_delete = self.flagged_files_to_delete();
+        let total_size = files_to_delete.iter().map(|f| f.file_metadata.size).sum();
+        (files_to_delete.len(), total_size)
+    }
     pub fn normal_mode(&mut self) {
         self.ui_mode = UiMode::Normal;
         self.render_and_update_board();
     }
     pub fn delete_file(&mut self, file_to_delete: &FileToDelete) {
-        let full_path = file_to_delete.full_path();
-
-        let metadata = fs::metadata(&full_path).expect("could not get file metadata");
-        let file_type = metadata.file_type();
-        let file_removed = if file_type.is_dir() {
-            fs::remove_dir_all(&full_path)
-        } else {
-            fs::remove_file(&full_path)
-        };
-        match file_removed {
+        match self.remove_single_file(file_to_delete) {
             Ok(_) => {
-                self.remove_file_from_ui(file_to_delete);
+                let path_to_file = &file_to_delete.path_to_file;
+                self.flagged_files.retain(|flagged| !flagged.starts_with(path_to_file.as_slice()));
+                self.sync_flagged_files_with_ui_effects();
                 self.ui_mode = UiMode::Normal;
                 self.render_and_update_board();
                 let _ = self.event_sender.send(Event::FileDeleted);
             },
             Err(msg) => {
-                self.ui_mode = UiMode::ErrorMessage(format!("{}", msg));
+                self.ui_mode = UiMode::ErrorMessage(msg);
                 self.render();
             }
         };
     }
+    pub fn delete_flagged_files(&mut self) {
+        let files_to_delete = self.flagged_files_to_delete();
+        let mut errors = vec![];
+        for file_to_delete in &files_to_delete {
+            match self.remove_single_file(file_to_delete) {
+                Ok(_) => {
+                    let path_to_file = &file_to_delete.path_to_file;
+                    self.flagged_files.retain(|flagged| !flagged.starts_with(path_to_file.as_slice()));
+                },
+                Err(msg) => errors.push(format!("{}: {}", file_to_delete.full_path().display(), msg)),
+            }
+        }
+        self.sync_flagged_files_with_ui_effects();
+        if errors.is_empty() {
+            self.ui_mode = UiMode::Normal;
+            self.render_and_update_board();
+            let _ = self.event_sender.send(Event::FileDeleted);
+        } else {
+            self.ui_mode = UiMode::ErrorMessage(errors.join("\n"));
+            self.render();
+        }
+    }
+    fn remove_single_file(&mut self, file_to_delete: &FileToDelete) -> Result<(), String> {
+        let full_path = file_to_delete.full_path();
+        if self.use_trash {
+            trash::delete(&full_path).map_err(|err| format!("{}", err))?;
+            self.trashed_files.push(file_to_delete.clone());
+        } else {
+            let metadata = fs::metadata(&full_path).map_err(|err| format!("{}", err))?;
+            let file_removed = if metadata.file_type().is_dir() {
+                fs::remove_dir_all(&full_path)
+            } else {
+                fs::remove_file(&full_path)
+            };
+            file_removed.map_err(|err| format!("{}", err))?;
+            self.file_tree.space_freed += file_to_delete.file_metadata.size;
+        }
+        self.remove_file_from_ui(file_to_delete);
+        Ok(())
+    }
+    pub fn empty_trash(&mut self) {
+        let reclaimed_size: u64 = self.trashed_files.iter().map(|file| file.file_metadata.size).sum();
+        self.file_tree.space_freed += reclaimed_size;
+        self.trashed_files.clear();
+        self.restore_list_selected_index = 0;
+    }
+    fn restore_from_os_trash(original_path: &Path) -> Result<(), String> {
+        let trash_item = trash::os_limited::list()
+            .map_err(|err| format!("{}", err))?
+            .into_iter()
+            .find(|item| item.original_path() == original_path)
+            .ok_or_else(|| "could not find the file in the OS trash".to_string())?;
+        trash::os_limited::restore_all(vec![trash_item]).map_err(|err| format!("{}", err))
+    }
+    pub fn show_restore_list(&mut self) {
+        self.restore_list_selected_index = 0;
+        self.ui_mode = UiMode::RestoreList;
+        self.render();
+    }
+    pub fn trashed_files(&self) -> &Vec<FileToDelete> {
+        &self.trashed_files
+    }
+    pub fn move_restore_list_selected_down(&mut self) {
+        if !self.trashed_files.is_empty() {
+            self.restore_list_selected_index = (self.restore_list_selected_index + 1) % self.trashed_files.len();
+        }
+        self.render();
+    }
+    pub fn move_restore_list_selected_up(&mut self) {
+        if !self.trashed_files.is_empty() {
+            self.restore_list_selected_index = match self.restore_list_selected_index {
+                0 => self.trashed_files.len() - 1,
+                i => i - 1,
+            };
+        }
+        self.render();
+    }
+    pub fn restore_selected_file(&mut self) {
+        if self.restore_list_selected_index >= self.trashed_files.len() {
+            return;
+        }
+        let full_path = self.trashed_files[self.restore_list_selected_index].full_path();
+        if let Err(msg) = Self::restore_from_os_trash(&full_path) {
+            self.ui_mode = UiMode::ErrorMessage(msg);
+            self.render();
+            return;
+        }
+        let restored_file = self.trashed_files.remove(self.restore_list_selected_index);
+        self.file_tree.add_file_back(&restored_file);
+        if self.restore_list_selected_index > 0 && self.restore_list_selected_index >= self.trashed_files.len() {
+            self.restore_list_selected_index -= 1;
+        }
+        self.render_and_update_board();
+    }
     pub fn increment_failed_to_read(&mut self) {
         self.file_tree.failed_to_read += 1;
     }
     fn remove_file_from_ui (&mut self, file_to_delete: &FileToDelete) {
-        self.file_tree.space_freed += file_to_delete.file_metadata.size;
         self.file_tree.delete_file(file_to_delete);
         self.board.reset_selected_index();
     }